@@ -1,6 +1,9 @@
 use anyhow::Result;
 use fallible_iterator::FallibleIterator;
-use sqlite3_parser::ast::{Cmd, PragmaBody, QualifiedName, Stmt};
+use sqlite3_parser::ast::{
+    Cmd, Expr, FromClause, InsertBody, Literal, Name, OneSelect, PragmaBody, QualifiedName,
+    ResultColumn, Select, SelectTable, Stmt, TransactionType,
+};
 use sqlite3_parser::lexer::sql::{Parser, ParserError};
 
 /// A group of statements to be executed together.
@@ -11,6 +14,16 @@ pub struct Statement {
     /// Is the statement an INSERT, UPDATE or DELETE?
     pub is_iud: bool,
     pub is_insert: bool,
+    /// Does this statement produce a result set? Always true for `Read`, and also true for an
+    /// IUD statement with a `RETURNING` clause.
+    pub returns_rows: bool,
+    /// The tables this statement reads from and writes to, as determined statically from its
+    /// AST.
+    pub table_access: TableAccess,
+    /// The schema alias targeted by an `ATTACH`/`DETACH DATABASE` statement, so a multi-tenant
+    /// layer can decide whether it's permitted. `None` for every other kind of statement, or if
+    /// the alias isn't a literal we can read statically.
+    pub attached_schema: Option<String>,
 }
 
 impl Default for Statement {
@@ -22,10 +35,19 @@ impl Default for Statement {
 /// Classify statement in categories of interest.
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum StmtKind {
-    /// The begining of a transaction
-    TxnBegin,
+    /// The begining of a transaction, carrying the `DEFERRED`/`IMMEDIATE`/`EXCLUSIVE`
+    /// qualifier it was opened with (`None` means `DEFERRED`, the default)
+    TxnBegin(Option<TransactionType>),
     /// The end of a transaction
     TxnEnd,
+    /// The opening (or nesting) of a savepoint
+    Savepoint,
+    /// The release of a savepoint
+    Release,
+    /// `ATTACH DATABASE`, changing the set of schemas visible on this connection
+    Attach,
+    /// `DETACH DATABASE`
+    Detach,
     Read,
     Write,
     Other,
@@ -40,8 +62,19 @@ impl StmtKind {
         match cmd {
             Cmd::Explain(_) => Some(Self::Other),
             Cmd::ExplainQueryPlan(_) => Some(Self::Other),
-            Cmd::Stmt(Stmt::Begin { .. }) => Some(Self::TxnBegin),
-            Cmd::Stmt(Stmt::Commit { .. } | Stmt::Rollback { .. }) => Some(Self::TxnEnd),
+            Cmd::Stmt(Stmt::Begin { tx_type, .. }) => Some(Self::TxnBegin(*tx_type)),
+            Cmd::Stmt(Stmt::Commit { .. }) => Some(Self::TxnEnd),
+            // `ROLLBACK TO SAVEPOINT foo` doesn't end the transaction, only a bare
+            // `ROLLBACK` (no savepoint name) does.
+            Cmd::Stmt(Stmt::Rollback {
+                savepoint_name: None,
+                ..
+            }) => Some(Self::TxnEnd),
+            Cmd::Stmt(Stmt::Rollback { .. }) => Some(Self::Other),
+            Cmd::Stmt(Stmt::Savepoint { .. }) => Some(Self::Savepoint),
+            Cmd::Stmt(Stmt::Release { .. }) => Some(Self::Release),
+            Cmd::Stmt(Stmt::Attach { .. }) => Some(Self::Attach),
+            Cmd::Stmt(Stmt::Detach { .. }) => Some(Self::Detach),
             Cmd::Stmt(
                 Stmt::CreateVirtualTable { tbl_name, .. }
                 | Stmt::CreateTable {
@@ -134,13 +167,302 @@ impl StmtKind {
     }
 }
 
+/// The set of tables a statement reads from and writes to, computed statically from its parsed
+/// AST. This mirrors what a SQLite update hook would report at runtime, but ahead of execution,
+/// so it can be used to invalidate cached query results and to route reads to a replica that
+/// isn't missing a write it depends on.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TableAccess {
+    pub reads: Vec<String>,
+    pub writes: Vec<String>,
+}
+
+/// Normalize a table name: a `TEMP` qualifier (matched case-insensitively, as SQLite does for
+/// schema names) is stripped, since temp tables are connection-local and unambiguous without it,
+/// but any other attached-db qualifier is kept so that e.g. `main.users` and `aux.users` are
+/// reported as distinct tables.
+fn table_name(name: &QualifiedName) -> String {
+    match &name.db_name {
+        Some(db) if !db.0.eq_ignore_ascii_case("TEMP") => format!("{}.{}", db.0, name.name.0),
+        _ => name.name.0.clone(),
+    }
+}
+
+/// Whether an INSERT/UPDATE/DELETE carries a non-empty `RETURNING` clause, and therefore
+/// produces a result set despite being a write.
+fn has_returning(cmd: &Cmd) -> bool {
+    match cmd {
+        Cmd::Stmt(
+            Stmt::Insert { returning, .. }
+            | Stmt::Update { returning, .. }
+            | Stmt::Delete { returning, .. },
+        ) => returning.as_ref().is_some_and(|r| !r.is_empty()),
+        _ => false,
+    }
+}
+
+/// Resolve the literal schema alias targeted by an `ATTACH`/`DETACH DATABASE` statement, so
+/// callers don't need to re-parse the statement to find it.
+fn attached_schema(cmd: &Cmd) -> Option<String> {
+    fn literal(expr: &Expr) -> Option<String> {
+        match expr {
+            Expr::Literal(Literal::String(s)) => Some(s.trim_matches('\'').to_string()),
+            Expr::Id(name) | Expr::Name(name) => Some(name.0.clone()),
+            _ => None,
+        }
+    }
+
+    match cmd {
+        Cmd::Stmt(Stmt::Attach { db_name, .. }) => literal(db_name),
+        // like `db_name` on `ATTACH`, the schema-name operand of `DETACH` parses as an
+        // expression (it can be any expr that evaluates to a schema name), not a bare name.
+        Cmd::Stmt(Stmt::Detach { name }) => literal(name),
+        _ => None,
+    }
+}
+
+impl TableAccess {
+    fn add_write(&mut self, name: &QualifiedName) {
+        let name = table_name(name);
+        if !self.writes.contains(&name) {
+            self.writes.push(name);
+        }
+    }
+
+    fn add_write_name(&mut self, name: &Name) {
+        let name = name.0.clone();
+        if !self.writes.contains(&name) {
+            self.writes.push(name);
+        }
+    }
+
+    fn add_read(&mut self, name: &QualifiedName) {
+        let name = table_name(name);
+        if !self.reads.contains(&name) {
+            self.reads.push(name);
+        }
+    }
+
+    /// Walk a `SELECT`, given the CTE aliases already in scope from an enclosing query. Aliases
+    /// defined by this query's own `WITH` clause are added to that inherited set *before* any
+    /// of the CTE bodies are walked, so a CTE can reference an earlier sibling or, for `WITH
+    /// RECURSIVE`, itself without it being mistaken for a physical table.
+    fn collect_select(&mut self, select: &Select, ctes: &[String]) {
+        let mut ctes = ctes.to_vec();
+        if let Some(with) = &select.with {
+            ctes.extend(with.ctes.iter().map(|cte| cte.tbl_name.0.clone()));
+            for cte in &with.ctes {
+                self.collect_select(&cte.select, &ctes);
+            }
+        }
+        self.collect_one_select(&select.body.select, &ctes);
+        if let Some(compounds) = &select.body.compounds {
+            for compound in compounds {
+                self.collect_one_select(&compound.select, &ctes);
+            }
+        }
+    }
+
+    fn collect_one_select(&mut self, select: &OneSelect, ctes: &[String]) {
+        if let OneSelect::Select {
+            columns,
+            from,
+            where_clause,
+            group_by,
+            ..
+        } = select
+        {
+            for column in columns {
+                if let ResultColumn::Expr(expr, _) = column {
+                    self.collect_expr(expr, ctes);
+                }
+            }
+            if let Some(from) = from {
+                self.collect_from(from, ctes);
+            }
+            if let Some(expr) = where_clause {
+                self.collect_expr(expr, ctes);
+            }
+            if let Some(group_by) = group_by {
+                for expr in &group_by.exprs {
+                    self.collect_expr(expr, ctes);
+                }
+                if let Some(having) = &group_by.having {
+                    self.collect_expr(having, ctes);
+                }
+            }
+        }
+    }
+
+    fn collect_from(&mut self, from: &FromClause, ctes: &[String]) {
+        if let Some(table) = &from.select {
+            self.collect_select_table(table, ctes);
+        }
+        if let Some(joins) = &from.joins {
+            for join in joins {
+                self.collect_select_table(&join.table, ctes);
+            }
+        }
+    }
+
+    fn collect_select_table(&mut self, table: &SelectTable, ctes: &[String]) {
+        match table {
+            SelectTable::Table(name, ..) | SelectTable::TableCall(name, ..) => {
+                // a bare reference to a CTE alias isn't a real table.
+                if name.db_name.is_none() && ctes.iter().any(|cte| *cte == name.name.0) {
+                    return;
+                }
+                self.add_read(name);
+            }
+            SelectTable::Select(select, _) => self.collect_select(select, ctes),
+            SelectTable::Sub(from, _) => self.collect_from(from, ctes),
+        }
+    }
+
+    /// Walk an expression looking for subqueries (`IN (SELECT ...)`, `EXISTS (SELECT ...)`,
+    /// scalar subqueries) and nested expressions in these positions — `IN (...)` lists,
+    /// `BETWEEN`, `CASE`, `LIKE`/`ESCAPE`, function-call arguments, casts, unary and binary
+    /// operators — so the tables they read are captured too, however deep they're nested. A
+    /// function's `FILTER`/`OVER` clause and `x IN table` are not walked. `ctes` carries the CTE aliases
+    /// in scope, so a subquery's own references to them aren't mistaken for physical tables.
+    fn collect_expr(&mut self, expr: &Expr, ctes: &[String]) {
+        match expr {
+            Expr::Subquery(select) | Expr::Exists(select) => self.collect_select(select, ctes),
+            Expr::InSelect { lhs, rhs, .. } => {
+                self.collect_expr(lhs, ctes);
+                self.collect_select(rhs, ctes);
+            }
+            Expr::InList { lhs, rhs, .. } => {
+                self.collect_expr(lhs, ctes);
+                if let Some(rhs) = rhs {
+                    for e in rhs {
+                        self.collect_expr(e, ctes);
+                    }
+                }
+            }
+            Expr::Between {
+                lhs, start, end, ..
+            } => {
+                self.collect_expr(lhs, ctes);
+                self.collect_expr(start, ctes);
+                self.collect_expr(end, ctes);
+            }
+            Expr::Like {
+                lhs, rhs, escape, ..
+            } => {
+                self.collect_expr(lhs, ctes);
+                self.collect_expr(rhs, ctes);
+                if let Some(escape) = escape {
+                    self.collect_expr(escape, ctes);
+                }
+            }
+            Expr::Case {
+                base,
+                when_then_pairs,
+                else_expr,
+            } => {
+                if let Some(base) = base {
+                    self.collect_expr(base, ctes);
+                }
+                for (when, then) in when_then_pairs {
+                    self.collect_expr(when, ctes);
+                    self.collect_expr(then, ctes);
+                }
+                if let Some(else_expr) = else_expr {
+                    self.collect_expr(else_expr, ctes);
+                }
+            }
+            Expr::FunctionCall { args, .. } => {
+                if let Some(args) = args {
+                    for arg in args {
+                        self.collect_expr(arg, ctes);
+                    }
+                }
+            }
+            Expr::Binary(lhs, _, rhs) => {
+                self.collect_expr(lhs, ctes);
+                self.collect_expr(rhs, ctes);
+            }
+            Expr::Unary(_, e)
+            | Expr::NotNull(e)
+            | Expr::IsNull(e)
+            | Expr::Collate(e, _)
+            | Expr::Cast { expr: e, .. } => self.collect_expr(e, ctes),
+            Expr::Parenthesized(exprs) => {
+                for e in exprs {
+                    self.collect_expr(e, ctes);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Walk a parsed statement and collect the tables it reads from and writes to.
+    pub fn collect(cmd: &Cmd) -> Self {
+        let mut access = Self::default();
+        match cmd {
+            Cmd::Stmt(Stmt::Insert {
+                tbl_name, body, ..
+            }) => {
+                access.add_write(tbl_name);
+                if let InsertBody::Select(select, _) = body {
+                    // no enclosing query, so no inherited CTEs to carry in.
+                    access.collect_select(select, &[]);
+                }
+            }
+            Cmd::Stmt(Stmt::Update {
+                tbl_name,
+                from,
+                where_clause,
+                ..
+            }) => {
+                access.add_write(tbl_name);
+                if let Some(from) = from {
+                    access.collect_from(from, &[]);
+                }
+                if let Some(expr) = where_clause {
+                    access.collect_expr(expr, &[]);
+                }
+            }
+            Cmd::Stmt(Stmt::Delete {
+                tbl_name,
+                where_clause,
+                ..
+            }) => {
+                access.add_write(tbl_name);
+                if let Some(expr) = where_clause {
+                    access.collect_expr(expr, &[]);
+                }
+            }
+            Cmd::Stmt(Stmt::DropTable { tbl_name, .. })
+            | Cmd::Stmt(Stmt::AlterTable(tbl_name, _))
+            | Cmd::Stmt(Stmt::CreateTrigger { tbl_name, .. }) => {
+                access.add_write(tbl_name);
+            }
+            Cmd::Stmt(Stmt::CreateIndex { tbl_name, .. }) => {
+                access.add_write_name(tbl_name);
+            }
+            Cmd::Stmt(Stmt::Select(select)) => access.collect_select(select, &[]),
+            _ => {}
+        }
+        access
+    }
+}
+
 /// The state of a transaction for a series of statement
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum State {
-    /// The txn in an opened state
-    Txn,
+    /// The txn is in an opened state, nested `depth` savepoints deep (a plain
+    /// `BEGIN`, or the implicit transaction opened by a top-level `SAVEPOINT`,
+    /// counts as depth 1)
+    Txn { depth: u32 },
     /// The txn in a closed state
     Init,
+    /// The connection has run a statement that durably mutates connection-level state (e.g.
+    /// `ATTACH DATABASE`), and must stay pinned to the primary for the rest of its lifetime.
+    /// Stateful pragmas like `case_sensitive_like` don't get here: they are rejected as
+    /// unsupported statements.
+    Primary,
     /// This is an invalid state for the state machine
     Invalid,
 }
@@ -148,16 +470,33 @@ pub enum State {
 impl State {
     pub fn step(&mut self, kind: StmtKind) {
         *self = match (*self, kind) {
-            (State::Txn, StmtKind::TxnBegin) | (State::Init, StmtKind::TxnEnd) => State::Invalid,
-            (State::Txn, StmtKind::TxnEnd) => State::Init,
-            (state, StmtKind::Other | StmtKind::Write | StmtKind::Read) => state,
+            // once invalid or pinned to the primary, the connection never leaves that state.
             (State::Invalid, _) => State::Invalid,
-            (State::Init, StmtKind::TxnBegin) => State::Txn,
+            (State::Primary, _) => State::Primary,
+            (_, StmtKind::Attach | StmtKind::Detach) => State::Primary,
+            (State::Txn { .. }, StmtKind::TxnBegin(_)) | (State::Init, StmtKind::TxnEnd) => {
+                State::Invalid
+            }
+            // an explicit COMMIT/ROLLBACK closes the transaction outright, regardless of
+            // how many savepoints are still nested inside it.
+            (State::Txn { .. }, StmtKind::TxnEnd) => State::Init,
+            (State::Init, StmtKind::Savepoint) => State::Txn { depth: 1 },
+            (State::Txn { depth }, StmtKind::Savepoint) => State::Txn { depth: depth + 1 },
+            (State::Init, StmtKind::Release) => State::Invalid,
+            (State::Txn { depth: 1 }, StmtKind::Release) => State::Init,
+            (State::Txn { depth }, StmtKind::Release) => State::Txn { depth: depth - 1 },
+            (state, StmtKind::Other | StmtKind::Write | StmtKind::Read) => state,
+            (State::Init, StmtKind::TxnBegin(_)) => State::Txn { depth: 1 },
         };
     }
 
+    /// Reset the transaction bookkeeping back to its initial state. A connection pinned to the
+    /// primary via [`State::Primary`] stays pinned: that reflects durable connection state (the
+    /// attached schemas), not the transaction, and resetting between batches doesn't undo it.
     pub fn reset(&mut self) {
-        *self = State::Init
+        if !matches!(self, State::Primary) {
+            *self = State::Init;
+        }
     }
 }
 
@@ -169,6 +508,9 @@ impl Statement {
             kind: StmtKind::Read,
             is_iud: false,
             is_insert: false,
+            returns_rows: true,
+            table_access: TableAccess::default(),
+            attached_schema: None,
         }
     }
 
@@ -184,6 +526,9 @@ impl Statement {
                     kind,
                     is_iud: false,
                     is_insert: false,
+                    returns_rows: false,
+                    table_access: TableAccess::default(),
+                    attached_schema: None,
                 });
             }
 
@@ -192,12 +537,18 @@ impl Statement {
                 Cmd::Stmt(Stmt::Insert { .. } | Stmt::Update { .. } | Stmt::Delete { .. })
             );
             let is_insert = matches!(c, Cmd::Stmt(Stmt::Insert { .. }));
+            let returns_rows = matches!(kind, StmtKind::Read) || has_returning(&c);
+            let table_access = TableAccess::collect(&c);
+            let attached_schema = attached_schema(&c);
 
             Ok(Statement {
                 stmt: c.to_string(),
                 kind,
                 is_iud,
                 is_insert,
+                returns_rows,
+                table_access,
+                attached_schema,
             })
         }
         // The parser needs to be boxed because it's large, and you don't want it on the stack.
@@ -225,7 +576,11 @@ impl Statement {
     pub fn is_read_only(&self) -> bool {
         matches!(
             self.kind,
-            StmtKind::Read | StmtKind::TxnEnd | StmtKind::TxnBegin
+            StmtKind::Read
+                | StmtKind::TxnEnd
+                | StmtKind::TxnBegin(_)
+                | StmtKind::Savepoint
+                | StmtKind::Release
         )
     }
 }
@@ -241,3 +596,295 @@ pub fn predict_final_state<'a>(
     }
     state
 }
+
+/// Whether a `BEGIN ... COMMIT/ROLLBACK` block can be served by a read replica.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TxnRouting {
+    /// The transaction only reads, and was opened `DEFERRED` (or without a qualifier), so it
+    /// can be served entirely by a replica.
+    ReplicaEligible,
+    /// The transaction was opened `IMMEDIATE`/`EXCLUSIVE`, or contains a write, so it must be
+    /// served by the primary.
+    PrimaryRequired,
+}
+
+/// Inspect a batch of statements and determine whether the transaction they form (if any) is
+/// eligible to be served by a read replica.
+///
+/// A `BEGIN DEFERRED` (or bare `BEGIN`) block is replica-eligible as long as every statement it
+/// contains is a `StmtKind::Read`; a `BEGIN IMMEDIATE`/`BEGIN EXCLUSIVE`, or any write inside the
+/// block, forces primary routing.
+pub fn classify_transaction<'a>(stmts: impl Iterator<Item = &'a Statement>) -> TxnRouting {
+    let mut stmts: Vec<&Statement> = stmts.collect();
+
+    if matches!(
+        stmts.first(),
+        Some(Statement {
+            kind: StmtKind::TxnBegin(Some(
+                TransactionType::Immediate | TransactionType::Exclusive
+            )),
+            ..
+        })
+    ) {
+        return TxnRouting::PrimaryRequired;
+    }
+
+    // Neither the opening `BEGIN` nor the closing `COMMIT`/`ROLLBACK` is itself a read, but
+    // they don't disqualify the transaction on their own; only what runs between them does.
+    if matches!(
+        stmts.first(),
+        Some(Statement {
+            kind: StmtKind::TxnBegin(_),
+            ..
+        })
+    ) {
+        stmts.remove(0);
+    }
+    if matches!(
+        stmts.last(),
+        Some(Statement {
+            kind: StmtKind::TxnEnd,
+            ..
+        })
+    ) {
+        stmts.pop();
+    }
+
+    if stmts.iter().all(|stmt| stmt.kind == StmtKind::Read) {
+        TxnRouting::ReplicaEligible
+    } else {
+        TxnRouting::PrimaryRequired
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(sql: &str) -> Vec<Statement> {
+        Statement::parse(sql).collect::<Result<Vec<_>>>().unwrap()
+    }
+
+    fn kinds(sql: &str) -> Vec<StmtKind> {
+        parse(sql).into_iter().map(|s| s.kind).collect()
+    }
+
+    #[test]
+    fn savepoint_opens_implicit_txn() {
+        let mut state = State::Init;
+        state.step(StmtKind::Savepoint);
+        assert_eq!(state, State::Txn { depth: 1 });
+    }
+
+    #[test]
+    fn nested_savepoints_increase_depth() {
+        let mut state = State::Init;
+        state.step(StmtKind::Savepoint);
+        state.step(StmtKind::Savepoint);
+        state.step(StmtKind::Savepoint);
+        assert_eq!(state, State::Txn { depth: 3 });
+    }
+
+    #[test]
+    fn release_decrements_depth_and_closes_at_zero() {
+        let mut state = State::Txn { depth: 2 };
+        state.step(StmtKind::Release);
+        assert_eq!(state, State::Txn { depth: 1 });
+        state.step(StmtKind::Release);
+        assert_eq!(state, State::Init);
+    }
+
+    #[test]
+    fn release_without_a_savepoint_is_invalid() {
+        let mut state = State::Init;
+        state.step(StmtKind::Release);
+        assert_eq!(state, State::Invalid);
+    }
+
+    #[test]
+    fn rollback_to_savepoint_stays_in_the_transaction() {
+        let mut state = State::Init;
+        state.step(StmtKind::Savepoint);
+        // `ROLLBACK TO SAVEPOINT foo` classifies as `Other` and, like any other non-txn
+        // statement, leaves the current state untouched.
+        state.step(StmtKind::Other);
+        assert_eq!(state, State::Txn { depth: 1 });
+    }
+
+    #[test]
+    fn explicit_commit_closes_regardless_of_depth() {
+        let mut state = State::Txn { depth: 3 };
+        state.step(StmtKind::TxnEnd);
+        assert_eq!(state, State::Init);
+    }
+
+    #[test]
+    fn second_begin_while_in_a_txn_is_invalid() {
+        let mut state = State::Txn { depth: 1 };
+        state.step(StmtKind::TxnBegin(None));
+        assert_eq!(state, State::Invalid);
+    }
+
+    #[test]
+    fn attach_pins_to_primary_and_survives_reset() {
+        let mut state = State::Init;
+        state.step(StmtKind::Attach);
+        assert_eq!(state, State::Primary);
+        state.step(StmtKind::Read);
+        assert_eq!(state, State::Primary);
+        state.reset();
+        assert_eq!(state, State::Primary);
+    }
+
+    #[test]
+    fn invalid_state_is_sticky() {
+        let mut state = State::Invalid;
+        state.step(StmtKind::Attach);
+        assert_eq!(state, State::Invalid);
+    }
+
+    #[test]
+    fn rollback_with_savepoint_name_is_not_a_txn_end() {
+        assert_eq!(
+            kinds("ROLLBACK TO SAVEPOINT foo"),
+            vec![StmtKind::Other]
+        );
+        assert_eq!(kinds("ROLLBACK"), vec![StmtKind::TxnEnd]);
+    }
+
+    #[test]
+    fn returns_rows_for_plain_iud_is_false() {
+        let stmts = parse("INSERT INTO t(a) VALUES (1)");
+        assert!(!stmts[0].returns_rows);
+        assert_eq!(stmts[0].kind, StmtKind::Write);
+    }
+
+    #[test]
+    fn returns_rows_for_iud_with_returning_is_true() {
+        for sql in [
+            "INSERT INTO t(a) VALUES (1) RETURNING a",
+            "UPDATE t SET a = 1 RETURNING a",
+            "DELETE FROM t RETURNING a",
+        ] {
+            let stmts = parse(sql);
+            assert!(stmts[0].returns_rows, "{sql}");
+            assert_eq!(stmts[0].kind, StmtKind::Write, "{sql}");
+        }
+    }
+
+    #[test]
+    fn table_access_for_select_covers_joins_and_subqueries() {
+        let stmts = parse(
+            "SELECT (SELECT x FROM sub) FROM t1 JOIN t2 ON t1.id = t2.id \
+             WHERE t1.a IN (SELECT a FROM t3)",
+        );
+        let mut reads = stmts[0].table_access.reads.clone();
+        reads.sort();
+        assert_eq!(reads, vec!["sub", "t1", "t2", "t3"]);
+        assert!(stmts[0].table_access.writes.is_empty());
+    }
+
+    #[test]
+    fn table_access_skips_cte_aliases() {
+        let stmts = parse("WITH cte AS (SELECT * FROM real_table) SELECT * FROM cte");
+        assert_eq!(stmts[0].table_access.reads, vec!["real_table"]);
+    }
+
+    #[test]
+    fn table_access_cte_can_reference_a_sibling_cte() {
+        let stmts = parse("WITH a AS (SELECT 1), b AS (SELECT * FROM a) SELECT * FROM b");
+        assert_eq!(stmts[0].table_access.reads, Vec::<String>::new());
+    }
+
+    #[test]
+    fn table_access_recursive_cte_does_not_read_itself() {
+        let stmts = parse(
+            "WITH RECURSIVE nums AS (SELECT 1 UNION ALL SELECT n+1 FROM nums) \
+             SELECT * FROM nums",
+        );
+        assert_eq!(stmts[0].table_access.reads, Vec::<String>::new());
+    }
+
+    #[test]
+    fn table_access_cte_referenced_from_inside_a_subquery() {
+        let stmts =
+            parse("WITH c AS (SELECT * FROM real_table) SELECT * FROM (SELECT * FROM c)");
+        assert_eq!(stmts[0].table_access.reads, vec!["real_table"]);
+    }
+
+    #[test]
+    fn table_access_walks_subqueries_in_in_list_between_case_like_and_function_args() {
+        let stmts = parse(
+            "SELECT CASE WHEN (SELECT 1 FROM a) THEN (SELECT 1 FROM b) ELSE (SELECT 1 FROM c) END \
+             FROM t \
+             WHERE x IN (1, (SELECT y FROM d)) \
+               AND x BETWEEN (SELECT y FROM e) AND (SELECT y FROM f) \
+               AND x LIKE (SELECT y FROM g) \
+               AND coalesce((SELECT y FROM h), 0) = 1",
+        );
+        let mut reads = stmts[0].table_access.reads.clone();
+        reads.sort();
+        assert_eq!(reads, vec!["a", "b", "c", "d", "e", "f", "g", "h", "t"]);
+    }
+
+    #[test]
+    fn table_access_for_insert_select_has_both_sides() {
+        let stmts = parse("INSERT INTO dst SELECT * FROM src");
+        assert_eq!(stmts[0].table_access.writes, vec!["dst"]);
+        assert_eq!(stmts[0].table_access.reads, vec!["src"]);
+    }
+
+    #[test]
+    fn table_access_normalizes_temp_but_keeps_other_schemas_distinct() {
+        let stmts = parse("SELECT * FROM temp.t");
+        assert_eq!(stmts[0].table_access.reads, vec!["t"]);
+
+        let stmts = parse("SELECT * FROM main.t");
+        assert_eq!(stmts[0].table_access.reads, vec!["main.t"]);
+    }
+
+    #[test]
+    fn classify_transaction_all_reads_is_replica_eligible() {
+        let stmts = parse("BEGIN; SELECT * FROM t; COMMIT;");
+        assert_eq!(
+            classify_transaction(stmts.iter()),
+            TxnRouting::ReplicaEligible
+        );
+    }
+
+    #[test]
+    fn classify_transaction_immediate_begin_forces_primary() {
+        let stmts = parse("BEGIN IMMEDIATE; SELECT * FROM t; COMMIT;");
+        assert_eq!(
+            classify_transaction(stmts.iter()),
+            TxnRouting::PrimaryRequired
+        );
+    }
+
+    #[test]
+    fn classify_transaction_write_inside_forces_primary() {
+        let stmts = parse("BEGIN; INSERT INTO t(a) VALUES (1); COMMIT;");
+        assert_eq!(
+            classify_transaction(stmts.iter()),
+            TxnRouting::PrimaryRequired
+        );
+    }
+
+    #[test]
+    fn classify_transaction_attach_inside_forces_primary() {
+        let stmts = parse("BEGIN; ATTACH DATABASE 'aux.db' AS aux; SELECT * FROM t; COMMIT;");
+        assert_eq!(
+            classify_transaction(stmts.iter()),
+            TxnRouting::PrimaryRequired
+        );
+    }
+
+    #[test]
+    fn attached_schema_is_captured_for_attach_and_detach() {
+        let stmts = parse("ATTACH DATABASE 'aux.db' AS aux");
+        assert_eq!(stmts[0].attached_schema.as_deref(), Some("aux"));
+
+        let stmts = parse("DETACH DATABASE aux");
+        assert_eq!(stmts[0].attached_schema.as_deref(), Some("aux"));
+    }
+}